@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, Utc};
 use futures::prelude::*;
 
 use common::{Error, GiphyGif};
 use crate::{Tx, PgPoolConn};
+use crate::query::QueryBuilder;
 
 //////////////////////////////////////////////////////////////////////////////////////////////////
 // FavoriteGif ///////////////////////////////////////////////////////////////////////////////////
@@ -21,17 +23,45 @@ pub struct SavedGif {
     pub title: String,
     /// The URL of the GIF.
     pub url: String,
-    /// The category given to this GIF by the user.
-    pub category: Option<String>,
+    /// The id of the category assigned to this GIF by the user, if any.
+    pub category: Option<i64>,
+    /// When this GIF was saved.
+    pub created_at: DateTime<Utc>,
+}
+
+/// Filter criteria for `SavedGif::search`, including the keyset pagination cursor.
+pub struct SavedGifFilter {
+    /// Restrict results to the given category id, if any.
+    pub category: Option<i64>,
+    /// Restrict results to titles containing this substring, matched case-insensitively.
+    pub title_contains: Option<String>,
+    /// Only return rows with an id greater than this cursor.
+    pub after: Option<i64>,
+    /// The maximum number of rows to return.
+    pub limit: i64,
 }
 
 impl SavedGif {
-    /// Insert a new record.
-    pub async fn insert(user: i64, gif: &GiphyGif, tx: &mut Tx) -> Result<Self, Error> {
+    /// Insert a new record, rejecting with a 429 if the user has already saved `daily_limit` gifs today.
+    /// Takes a Postgres advisory lock on `user` before counting, so a concurrent save for the same
+    /// user blocks until this transaction commits instead of reading the same pre-insert count under
+    /// READ COMMITTED — without the lock, two concurrent saves could each see `saved_today` below the
+    /// limit and both insert.
+    pub async fn insert(user: i64, gif: &GiphyGif, daily_limit: i64, tx: &mut Tx) -> Result<Self, Error> {
+        sqlx::query!("SELECT pg_advisory_xact_lock($1);", user)
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::from)?;
+
+        let saved_today = User::saves_today(user, &mut *tx).await?;
+        if saved_today >= daily_limit {
+            return Err(Error::new("daily save limit reached", 429, None));
+        }
+
         Ok(sqlx::query_as!(
             SavedGif,
-            r#"INSERT INTO public.saved_gifs ("user", giphy_id, title, url, category) VALUES ($1, $2, $3, $4, $5) RETURNING *;"#,
-            user, gif.id.clone(), gif.title.clone(), gif.url.clone(), gif.category.clone(),
+            r#"INSERT INTO public.saved_gifs ("user", giphy_id, title, url, category) VALUES ($1, $2, $3, $4, NULL) RETURNING *;"#,
+            user, gif.id.clone(), gif.title.clone(), gif.url.clone(),
         )
         .fetch_one(tx)
         .await
@@ -50,6 +80,35 @@ impl SavedGif {
             .await?)
     }
 
+    /// Search the gifs saved by the specified user using keyset pagination, returning the matching
+    /// page of rows along with the last row's id to pass back as the next `after` cursor.
+    pub async fn search(user: i64, filter: SavedGifFilter, db: &mut PgPoolConn) -> Result<(Vec<SavedGif>, Option<i64>), Error> {
+        let mut qb = QueryBuilder::new("SELECT * FROM public.saved_gifs");
+        qb.push_cond(|n| format!(r#""user"=${}"#, n), user);
+        if let Some(category) = filter.category {
+            qb.push_cond(|n| format!("category=${}", n), category);
+        }
+        if let Some(term) = filter.title_contains {
+            qb.push_cond(|n| format!("title ILIKE ${}", n), format!("%{}%", term));
+        }
+        qb.push_cond(|n| format!("id > ${}", n), filter.after.unwrap_or(0));
+        let limit_param = qb.bind(filter.limit);
+        qb.push("ORDER BY id ASC");
+        qb.push(&format!("LIMIT ${};", limit_param));
+        let (sql, args) = qb.build();
+
+        let stream = sqlx::query_as_with::<_, SavedGif, _>(&sql, args).fetch(db);
+        let page = stream
+            .try_fold(vec![], |mut acc, gif| async move {
+                acc.push(gif);
+                Ok(acc)
+            })
+            .map_err(Error::from)
+            .await?;
+        let next_after = page.last().map(|gif| gif.id);
+        Ok((page, next_after))
+    }
+
     /// Find all gifs saved by the specified user matching the set of IDs.
     pub async fn for_user_matching_ids<'a>(user: i64, ids: &'a [String], db: &'a mut PgPoolConn) -> Result<HashMap<String, SavedGif>, Error> {
         let stream = sqlx::query_as!(SavedGif, r#"SELECT * FROM public.saved_gifs WHERE "user"=$1 AND giphy_id=ANY($2);"#, user, ids)
@@ -63,17 +122,53 @@ impl SavedGif {
             .await?)
     }
 
-    /// Set a new category for the target user's gif, returning None if the target gif does not exist for the given user.
-    pub async fn set_category(user: i64, gif: String, category: String, tx: &mut Tx) -> Result<Option<Self>, Error> {
+    /// Set a new category for the target user's gif, returning None if the gif does not exist for
+    /// the given user or if `category` does not belong to that same user (preventing a user from
+    /// pointing their gif at someone else's category id).
+    pub async fn set_category(user: i64, gif: String, category: i64, tx: &mut Tx) -> Result<Option<Self>, Error> {
         Ok(sqlx::query_as!(
             SavedGif,
-            r#"UPDATE public.saved_gifs SET category=$1 WHERE "user"=$2 AND giphy_id=$3 RETURNING *;"#,
+            r#"UPDATE public.saved_gifs s SET category=$1 FROM public.categories c
+               WHERE c.id=$1 AND c."user"=$2 AND s."user"=$2 AND s.giphy_id=$3
+               RETURNING s.*;"#,
             category, user, gif,
         )
         .fetch_optional(tx)
         .await
         .map_err(Error::from)?)
     }
+
+    /// Move all of the given gifs to a new category in a single round-trip, returning all affected
+    /// rows. Returns no rows if `category` does not belong to `user`, same as `set_category`.
+    pub async fn set_category_bulk(user: i64, giphy_ids: &[String], category: i64, tx: &mut Tx) -> Result<Vec<Self>, Error> {
+        let stream = sqlx::query_as!(
+            SavedGif,
+            r#"UPDATE public.saved_gifs s SET category=$1 FROM public.categories c
+               WHERE c.id=$1 AND c."user"=$2 AND s."user"=$2 AND s.giphy_id = ANY($3)
+               RETURNING s.*;"#,
+            category, user, giphy_ids,
+        )
+        .fetch(tx);
+        Ok(stream
+            .try_fold(vec![], |mut acc, gif| async move {
+                acc.push(gif);
+                Ok(acc)
+            })
+            .map_err(Error::from)
+            .await?)
+    }
+
+    /// Delete the target user's saved gif, returning None if it does not exist for the given user.
+    pub async fn delete(user: i64, giphy_id: String, tx: &mut Tx) -> Result<Option<Self>, Error> {
+        Ok(sqlx::query_as!(
+            SavedGif,
+            r#"DELETE FROM public.saved_gifs WHERE "user"=$1 AND giphy_id=$2 RETURNING *;"#,
+            user, giphy_id,
+        )
+        .fetch_optional(tx)
+        .await
+        .map_err(Error::from)?)
+    }
 }
 
 impl From<SavedGif> for GiphyGif {
@@ -83,11 +178,105 @@ impl From<SavedGif> for GiphyGif {
             title: src.title,
             url: src.url,
             is_saved: true,
-            category: src.category,
+            // The category name now lives in `categories`; callers that need it should join
+            // against `Category::all_for_user` rather than relying on this conversion.
+            category: None,
         }
     }
 }
 
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Category //////////////////////////////////////////////////////////////////////////////////////
+
+/// A user-defined category used to organize their saved GIFs.
+#[derive(Clone, sqlx::FromRow)]
+pub struct Category {
+    /// Object ID.
+    pub id: i64,
+    /// The ID of the user which owns this category.
+    pub user: i64,
+    /// The category's name.
+    pub name: String,
+}
+
+/// A `Category` along with the number of saved gifs currently assigned to it.
+#[derive(Clone, sqlx::FromRow)]
+pub struct CategoryWithCount {
+    /// Object ID.
+    pub id: i64,
+    /// The ID of the user which owns this category.
+    pub user: i64,
+    /// The category's name.
+    pub name: String,
+    /// The number of saved gifs assigned to this category.
+    pub count: i64,
+}
+
+impl Category {
+    /// Insert a new record.
+    pub async fn insert(user: i64, name: String, tx: &mut Tx) -> Result<Self, Error> {
+        Ok(sqlx::query_as!(
+            Category,
+            r#"INSERT INTO public.categories ("user", name) VALUES ($1, $2) RETURNING *;"#,
+            user, name,
+        )
+        .fetch_one(tx)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::Database(dberr) => {
+                match dberr.constraint_name() {
+                    Some(constraint) if constraint == "categories_user_name_key" => {
+                        Error::new("You already have a category with that name.", 400, None)
+                    }
+                    _ => Error::from(sqlx::Error::Database(dberr)), // Just resurface the error.
+                }
+            }
+            _ => Error::new_ise(),
+        })?)
+    }
+
+    /// Find all categories belonging to the specified user, along with each one's saved-gif count.
+    pub async fn all_for_user(user: i64, db: &mut PgPoolConn) -> Result<Vec<CategoryWithCount>, Error> {
+        let stream = sqlx::query_as!(
+            CategoryWithCount,
+            r#"SELECT c.id, c."user", c.name, COUNT(s.id) AS "count!" FROM public.categories c
+               LEFT JOIN public.saved_gifs s ON s.category = c.id AND s."user" = c."user"
+               WHERE c."user"=$1 GROUP BY c.id;"#,
+            user,
+        )
+        .fetch(db);
+        Ok(stream
+            .try_fold(vec![], |mut acc, category| async move {
+                acc.push(category);
+                Ok(acc)
+            })
+            .map_err(Error::from)
+            .await?)
+    }
+
+    /// Rename the target user's category, returning None if it does not exist for the given user.
+    pub async fn rename(user: i64, id: i64, name: String, tx: &mut Tx) -> Result<Option<Self>, Error> {
+        Ok(sqlx::query_as!(
+            Category,
+            r#"UPDATE public.categories SET name=$1 WHERE "user"=$2 AND id=$3 RETURNING *;"#,
+            name, user, id,
+        )
+        .fetch_optional(tx)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::Database(dberr) => {
+                match dberr.constraint_name() {
+                    Some(constraint) if constraint == "categories_user_name_key" => {
+                        Error::new("You already have a category with that name.", 400, None)
+                    }
+                    _ => Error::from(sqlx::Error::Database(dberr)), // Just resurface the error.
+                }
+            }
+            _ => Error::new_ise(),
+        })?)
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////////////////////////
 // User //////////////////////////////////////////////////////////////////////////////////////////
 
@@ -141,4 +330,20 @@ impl User {
     pub fn into_common(self, jwt: String) -> common::User {
         common::User{id: self.id, email: self.email, jwt}
     }
+
+    /// Count how many gifs this user has saved since the start of the current day. Takes any
+    /// Postgres executor so callers can run it against a plain connection or inside a `Tx`
+    /// (e.g. `SavedGif::insert`'s quota check).
+    pub async fn saves_today<'e, E>(user: i64, db: E) -> Result<i64, Error>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM public.saved_gifs WHERE "user"=$1 AND created_at >= date_trunc('day', now());"#,
+            user,
+        )
+        .fetch_one(db)
+        .await
+        .map_err(Error::from)?)
+    }
 }
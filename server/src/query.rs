@@ -0,0 +1,59 @@
+//! A small dynamic query builder for composing `WHERE` clauses with correctly numbered Postgres
+//! placeholders, for the cases where sqlx's compile-time `query_as!` macros are too rigid to
+//! express a set of optional filters without hand-writing every combination.
+
+use sqlx::postgres::{PgArguments, Postgres};
+use sqlx::Arguments;
+
+/// Incrementally builds a query, tracking Postgres `$N` placeholder numbering as conditions and
+/// bind arguments are appended, then hands off a `(sql, args)` pair to `sqlx::query_as_with`.
+pub struct QueryBuilder {
+    sql: String,
+    args: PgArguments,
+    next_param: usize,
+    has_where: bool,
+}
+
+impl QueryBuilder {
+    /// Start building a query from the given base SQL, e.g. `SELECT * FROM public.saved_gifs`.
+    pub fn new(base: impl Into<String>) -> Self {
+        Self{sql: base.into(), args: PgArguments::default(), next_param: 1, has_where: false}
+    }
+
+    /// Append a `WHERE`/`AND`-joined condition along with its bind argument. `clause` is given the
+    /// placeholder number to interpolate, e.g. `|n| format!("category=${}", n)`.
+    pub fn push_cond<T>(&mut self, clause: impl FnOnce(usize) -> String, value: T)
+    where
+        T: for<'q> sqlx::Encode<'q, Postgres> + sqlx::Type<Postgres> + Send + 'static,
+    {
+        self.sql.push_str(if self.has_where { " AND " } else { " WHERE " });
+        self.sql.push_str(&clause(self.next_param));
+        self.args.add(value);
+        self.next_param += 1;
+        self.has_where = true;
+    }
+
+    /// Bind a value with no accompanying condition, returning its placeholder number so it can be
+    /// interpolated into raw SQL appended via `push` (e.g. for `ORDER BY`/`LIMIT`).
+    pub fn bind<T>(&mut self, value: T) -> usize
+    where
+        T: for<'q> sqlx::Encode<'q, Postgres> + sqlx::Type<Postgres> + Send + 'static,
+    {
+        let param = self.next_param;
+        self.args.add(value);
+        self.next_param += 1;
+        param
+    }
+
+    /// Append raw SQL with no bound argument.
+    pub fn push(&mut self, raw: &str) -> &mut Self {
+        self.sql.push(' ');
+        self.sql.push_str(raw);
+        self
+    }
+
+    /// Consume the builder, returning the finished SQL string and its bound arguments.
+    pub fn build(self) -> (String, PgArguments) {
+        (self.sql, self.args)
+    }
+}